@@ -1,9 +1,11 @@
 use super::instructions::Inst;
+use super::value::Value;
 
 pub struct Assembly {
     pub entry: u16,
     pub name: String,
     pub functions: Vec<FuncDef>,
+    pub globals: Vec<u32>,
 }
 
 impl Assembly {
@@ -46,7 +48,7 @@ pub struct ManagedFuncDef {
     pub name: String,
     pub args: u16,
     pub returns: bool,
-    pub default_locals: Vec<u32>,
+    pub default_locals: Vec<Value>,
     pub body: Vec<Inst>,
 }
 
@@ -60,10 +62,16 @@ pub struct NativeFuncDef {
 pub fn print_assembly(asm: &Assembly) {
     println!("Assembly '{}' with entry point '{}':", &asm.name, asm.get_entry().name());
     for (idx, func) in asm.functions.iter().enumerate() {
-        let func = func.as_managed().unwrap();
-        println!(" Function #{} '{}' - locals: {}:", idx, func.name, func.default_locals.len());
-        for val in func.body.iter() {
-            println!("  {}", val);
+        match func {
+            FuncDef::Managed(func) => {
+                println!(" Function #{} '{}' - locals: {}:", idx, func.name, func.default_locals.len());
+                for val in func.body.iter() {
+                    println!("  {}", val);
+                }
+            }
+            FuncDef::Native(func) => {
+                println!(" Function #{} '{}' - native, args: {}, returns: {}", idx, func.name, func.args, func.returns);
+            }
         }
     }
 }
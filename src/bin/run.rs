@@ -8,7 +8,13 @@ use vm::interpreter::execute_assembly;
 fn main() {
     let path = args().nth(1).unwrap();
     let mut loader = Loader::new();
-    let asm = loader.load(&path);
+    let asm = if path.ends_with(".dvmx") {
+        loader.load_binary(&path).unwrap_or_else(|err| panic!("failed to load '{}': {}", path, err))
+    } else {
+        loader.load(&path)
+    };
     print_assembly(&asm);
-    execute_assembly(&asm)
+    if let Err(err) = execute_assembly(&asm) {
+        eprintln!("vm error: {}", err);
+    }
 }
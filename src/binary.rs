@@ -0,0 +1,432 @@
+use ::std::fmt;
+use super::assembly::{Assembly, FuncDef, ManagedFuncDef, NativeFuncDef};
+use super::instructions::Inst;
+use super::value::Value;
+
+/// Everything that can go wrong decoding an untrusted `DVMX` module, so a
+/// truncated or hand-crafted file is reported rather than crashing the host.
+#[derive(Debug)]
+pub enum BinaryError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnexpectedEof,
+    InvalidUtf8,
+    UnknownFunctionTag(u8),
+    UnknownValueTag(u8),
+    UnknownOpcode(u8),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            BinaryError::BadMagic => write!(f, "not a DVMX module"),
+            BinaryError::UnsupportedVersion(version) => write!(f, "unsupported module version: {}", version),
+            BinaryError::UnexpectedEof => write!(f, "unexpected end of module"),
+            BinaryError::InvalidUtf8 => write!(f, "invalid utf-8 in module string"),
+            BinaryError::UnknownFunctionTag(tag) => write!(f, "unknown function tag: {}", tag),
+            BinaryError::UnknownValueTag(tag) => write!(f, "unknown value tag: {}", tag),
+            BinaryError::UnknownOpcode(op) => write!(f, "unknown opcode: {}", op),
+        }
+    }
+}
+
+const MAGIC: &[u8; 4] = b"DVMX";
+const VERSION: u16 = 4;
+
+const OP_LDARG: u8 = 0;
+const OP_STARG: u8 = 1;
+const OP_ADD_U: u8 = 2;
+const OP_ADD_S: u8 = 3;
+const OP_SUB_U: u8 = 4;
+const OP_SUB_S: u8 = 5;
+const OP_MUL_U: u8 = 6;
+const OP_MUL_S: u8 = 7;
+const OP_DIV_U: u8 = 8;
+const OP_DIV_S: u8 = 9;
+const OP_MOD_U: u8 = 10;
+const OP_MOD_S: u8 = 11;
+const OP_SHL: u8 = 12;
+const OP_SHR_U: u8 = 13;
+const OP_SHR_S: u8 = 14;
+const OP_AND: u8 = 15;
+const OP_OR: u8 = 16;
+const OP_XOR: u8 = 17;
+const OP_CMP_EQ: u8 = 18;
+const OP_CMP_LT_U: u8 = 19;
+const OP_CMP_LT_S: u8 = 20;
+const OP_CMP_GT_U: u8 = 21;
+const OP_CMP_GT_S: u8 = 22;
+const OP_JUMP: u8 = 23;
+const OP_BEQ: u8 = 24;
+const OP_BREAKPOINT: u8 = 25;
+const OP_CALL: u8 = 26;
+const OP_RET: u8 = 27;
+const OP_TRY: u8 = 28;
+const OP_ENDTRY: u8 = 29;
+const OP_THROW: u8 = 30;
+const OP_LDC_I32: u8 = 31;
+const OP_LDC_I64: u8 = 32;
+const OP_LDC_F32: u8 = 33;
+const OP_LDC_F64: u8 = 34;
+const OP_ADD_F32: u8 = 35;
+const OP_ADD_F64: u8 = 36;
+const OP_SUB_F32: u8 = 37;
+const OP_SUB_F64: u8 = 38;
+const OP_MUL_F32: u8 = 39;
+const OP_MUL_F64: u8 = 40;
+const OP_DIV_F32: u8 = 41;
+const OP_DIV_F64: u8 = 42;
+const OP_LDGLOBAL: u8 = 43;
+const OP_STGLOBAL: u8 = 44;
+const OP_ADD_I64: u8 = 45;
+const OP_SUB_I64: u8 = 46;
+const OP_MUL_I64: u8 = 47;
+const OP_DIV_I64: u8 = 48;
+
+const TAG_MANAGED: u8 = 0;
+const TAG_NATIVE: u8 = 1;
+
+const VALUE_I32: u8 = 0;
+const VALUE_I64: u8 = 1;
+const VALUE_F32: u8 = 2;
+const VALUE_F64: u8 = 3;
+
+/// Serializes an `Assembly` into the `DVMX` binary module format: a magic +
+/// version header, the entry index, module name and globals, followed by a
+/// function table with branch/call targets
+/// already resolved to absolute offsets (no placeholder pass needed on load).
+pub fn write_binary(asm: &Assembly) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&asm.entry.to_le_bytes());
+    write_string(&mut out, &asm.name);
+    out.extend_from_slice(&(asm.globals.len() as u32).to_le_bytes());
+    for global in &asm.globals {
+        out.extend_from_slice(&global.to_le_bytes());
+    }
+    out.extend_from_slice(&(asm.functions.len() as u16).to_le_bytes());
+    for func in &asm.functions {
+        write_func(&mut out, func);
+    }
+    out
+}
+
+/// Decodes a `DVMX` binary module back into an `Assembly`, the inverse of
+/// `write_binary`. Fails with a `BinaryError` instead of panicking on
+/// truncated or otherwise malformed input.
+pub fn disassemble(bytes: &[u8]) -> Result<Assembly, BinaryError> {
+    if bytes.len() < MAGIC.len() || &bytes[0..MAGIC.len()] != MAGIC {
+        return Err(BinaryError::BadMagic);
+    }
+    let mut pos = MAGIC.len();
+    let version = read_u16(bytes, &mut pos)?;
+    if version != VERSION {
+        return Err(BinaryError::UnsupportedVersion(version));
+    }
+    let entry = read_u16(bytes, &mut pos)?;
+    let name = read_string(bytes, &mut pos)?;
+    let globals_count = read_u32(bytes, &mut pos)?;
+    let mut globals = Vec::with_capacity((globals_count as usize).min(bytes.len() - pos));
+    for _ in 0..globals_count {
+        globals.push(read_u32(bytes, &mut pos)?);
+    }
+    let function_count = read_u16(bytes, &mut pos)?;
+    let mut functions = Vec::with_capacity(function_count as usize);
+    for _ in 0..function_count {
+        functions.push(read_func(bytes, &mut pos)?);
+    }
+    Ok(Assembly { entry, name, functions, globals })
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_func(out: &mut Vec<u8>, func: &FuncDef) {
+    match func {
+        FuncDef::Managed(func) => {
+            out.push(TAG_MANAGED);
+            write_string(out, &func.name);
+            out.extend_from_slice(&func.args.to_le_bytes());
+            out.push(func.returns as u8);
+            out.extend_from_slice(&(func.default_locals.len() as u32).to_le_bytes());
+            for local in &func.default_locals {
+                write_value(out, *local);
+            }
+            let body = write_body(&func.body);
+            out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            out.extend_from_slice(&body);
+        }
+        FuncDef::Native(func) => {
+            out.push(TAG_NATIVE);
+            write_string(out, &func.name);
+            out.extend_from_slice(&func.args.to_le_bytes());
+            out.push(func.returns as u8);
+        }
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: Value) {
+    match value {
+        Value::I32(v) => { out.push(VALUE_I32); out.extend_from_slice(&v.to_le_bytes()); }
+        Value::I64(v) => { out.push(VALUE_I64); out.extend_from_slice(&v.to_le_bytes()); }
+        Value::F32(v) => { out.push(VALUE_F32); out.extend_from_slice(&v.to_bits().to_le_bytes()); }
+        Value::F64(v) => { out.push(VALUE_F64); out.extend_from_slice(&v.to_bits().to_le_bytes()); }
+    }
+}
+
+fn read_value(bytes: &[u8], pos: &mut usize) -> Result<Value, BinaryError> {
+    let tag = read_u8(bytes, pos)?;
+    Ok(match tag {
+        VALUE_I32 => Value::I32(read_u32(bytes, pos)?),
+        VALUE_I64 => Value::I64(read_u64(bytes, pos)?),
+        VALUE_F32 => Value::F32(f32::from_bits(read_u32(bytes, pos)?)),
+        VALUE_F64 => Value::F64(f64::from_bits(read_u64(bytes, pos)?)),
+        tag => return Err(BinaryError::UnknownValueTag(tag)),
+    })
+}
+
+fn write_body(body: &[Inst]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for inst in body {
+        match *inst {
+            Inst::ldarg(n) => { out.push(OP_LDARG); out.push(n); }
+            Inst::starg(n) => { out.push(OP_STARG); out.push(n); }
+            Inst::add_u => out.push(OP_ADD_U),
+            Inst::add_s => out.push(OP_ADD_S),
+            Inst::sub_u => out.push(OP_SUB_U),
+            Inst::sub_s => out.push(OP_SUB_S),
+            Inst::mul_u => out.push(OP_MUL_U),
+            Inst::mul_s => out.push(OP_MUL_S),
+            Inst::div_u => out.push(OP_DIV_U),
+            Inst::div_s => out.push(OP_DIV_S),
+            Inst::mod_u => out.push(OP_MOD_U),
+            Inst::mod_s => out.push(OP_MOD_S),
+            Inst::shl => out.push(OP_SHL),
+            Inst::shr_u => out.push(OP_SHR_U),
+            Inst::shr_s => out.push(OP_SHR_S),
+            Inst::and => out.push(OP_AND),
+            Inst::or => out.push(OP_OR),
+            Inst::xor => out.push(OP_XOR),
+            Inst::cmp_eq => out.push(OP_CMP_EQ),
+            Inst::cmp_lt_u => out.push(OP_CMP_LT_U),
+            Inst::cmp_lt_s => out.push(OP_CMP_LT_S),
+            Inst::cmp_gt_u => out.push(OP_CMP_GT_U),
+            Inst::cmp_gt_s => out.push(OP_CMP_GT_S),
+            Inst::jump(target) => { out.push(OP_JUMP); out.extend_from_slice(&target.to_le_bytes()); }
+            Inst::beq(target) => { out.push(OP_BEQ); out.extend_from_slice(&target.to_le_bytes()); }
+            Inst::breakpoint => out.push(OP_BREAKPOINT),
+            Inst::call(idx) => { out.push(OP_CALL); out.extend_from_slice(&idx.to_le_bytes()); }
+            Inst::ret => out.push(OP_RET),
+            Inst::try_(target) => { out.push(OP_TRY); out.extend_from_slice(&target.to_le_bytes()); }
+            Inst::endtry => out.push(OP_ENDTRY),
+            Inst::throw => out.push(OP_THROW),
+            Inst::ldc_i32(val) => { out.push(OP_LDC_I32); out.extend_from_slice(&val.to_le_bytes()); }
+            Inst::ldc_i64(val) => { out.push(OP_LDC_I64); out.extend_from_slice(&val.to_le_bytes()); }
+            Inst::ldc_f32(val) => { out.push(OP_LDC_F32); out.extend_from_slice(&val.to_bits().to_le_bytes()); }
+            Inst::ldc_f64(val) => { out.push(OP_LDC_F64); out.extend_from_slice(&val.to_bits().to_le_bytes()); }
+            Inst::add_f32 => out.push(OP_ADD_F32),
+            Inst::add_f64 => out.push(OP_ADD_F64),
+            Inst::sub_f32 => out.push(OP_SUB_F32),
+            Inst::sub_f64 => out.push(OP_SUB_F64),
+            Inst::mul_f32 => out.push(OP_MUL_F32),
+            Inst::mul_f64 => out.push(OP_MUL_F64),
+            Inst::div_f32 => out.push(OP_DIV_F32),
+            Inst::div_f64 => out.push(OP_DIV_F64),
+            Inst::ldglobal(idx) => { out.push(OP_LDGLOBAL); out.extend_from_slice(&idx.to_le_bytes()); }
+            Inst::stglobal(idx) => { out.push(OP_STGLOBAL); out.extend_from_slice(&idx.to_le_bytes()); }
+            Inst::add_i64 => out.push(OP_ADD_I64),
+            Inst::sub_i64 => out.push(OP_SUB_I64),
+            Inst::mul_i64 => out.push(OP_MUL_I64),
+            Inst::div_i64 => out.push(OP_DIV_I64),
+        }
+    }
+    out
+}
+
+fn read_func(bytes: &[u8], pos: &mut usize) -> Result<FuncDef, BinaryError> {
+    let tag = read_u8(bytes, pos)?;
+    let name = read_string(bytes, pos)?;
+    let args = read_u16(bytes, pos)?;
+    let returns = read_u8(bytes, pos)? != 0;
+    Ok(match tag {
+        TAG_MANAGED => {
+            let locals_count = read_u32(bytes, pos)?;
+            let mut default_locals = Vec::with_capacity((locals_count as usize).min(bytes.len() - *pos));
+            for _ in 0..locals_count {
+                default_locals.push(read_value(bytes, pos)?);
+            }
+            let body_len = read_u32(bytes, pos)? as usize;
+            let body_end = pos.checked_add(body_len).filter(|&end| end <= bytes.len()).ok_or(BinaryError::UnexpectedEof)?;
+            let body = read_body(&bytes[*pos..body_end])?;
+            *pos = body_end;
+            FuncDef::Managed(ManagedFuncDef { name, args, returns, default_locals, body })
+        }
+        TAG_NATIVE => FuncDef::Native(NativeFuncDef { name, args, returns }),
+        tag => return Err(BinaryError::UnknownFunctionTag(tag)),
+    })
+}
+
+fn read_body(bytes: &[u8]) -> Result<Vec<Inst>, BinaryError> {
+    let mut pos = 0usize;
+    let mut body = Vec::new();
+    while pos < bytes.len() {
+        let opcode = read_u8(bytes, &mut pos)?;
+        let inst = match opcode {
+            OP_LDARG => { let n = read_u8(bytes, &mut pos)?; Inst::ldarg(n) }
+            OP_STARG => { let n = read_u8(bytes, &mut pos)?; Inst::starg(n) }
+            OP_ADD_U => Inst::add_u,
+            OP_ADD_S => Inst::add_s,
+            OP_SUB_U => Inst::sub_u,
+            OP_SUB_S => Inst::sub_s,
+            OP_MUL_U => Inst::mul_u,
+            OP_MUL_S => Inst::mul_s,
+            OP_DIV_U => Inst::div_u,
+            OP_DIV_S => Inst::div_s,
+            OP_MOD_U => Inst::mod_u,
+            OP_MOD_S => Inst::mod_s,
+            OP_SHL => Inst::shl,
+            OP_SHR_U => Inst::shr_u,
+            OP_SHR_S => Inst::shr_s,
+            OP_AND => Inst::and,
+            OP_OR => Inst::or,
+            OP_XOR => Inst::xor,
+            OP_CMP_EQ => Inst::cmp_eq,
+            OP_CMP_LT_U => Inst::cmp_lt_u,
+            OP_CMP_LT_S => Inst::cmp_lt_s,
+            OP_CMP_GT_U => Inst::cmp_gt_u,
+            OP_CMP_GT_S => Inst::cmp_gt_s,
+            OP_JUMP => { let target = read_u32(bytes, &mut pos)?; Inst::jump(target) }
+            OP_BEQ => { let target = read_u32(bytes, &mut pos)?; Inst::beq(target) }
+            OP_BREAKPOINT => Inst::breakpoint,
+            OP_CALL => { let idx = read_u16(bytes, &mut pos)?; Inst::call(idx) }
+            OP_RET => Inst::ret,
+            OP_TRY => { let target = read_u32(bytes, &mut pos)?; Inst::try_(target) }
+            OP_ENDTRY => Inst::endtry,
+            OP_THROW => Inst::throw,
+            OP_LDC_I32 => { let val = read_u32(bytes, &mut pos)?; Inst::ldc_i32(val) }
+            OP_LDC_I64 => { let val = read_u64(bytes, &mut pos)?; Inst::ldc_i64(val) }
+            OP_LDC_F32 => { let val = f32::from_bits(read_u32(bytes, &mut pos)?); Inst::ldc_f32(val) }
+            OP_LDC_F64 => { let val = f64::from_bits(read_u64(bytes, &mut pos)?); Inst::ldc_f64(val) }
+            OP_ADD_F32 => Inst::add_f32,
+            OP_ADD_F64 => Inst::add_f64,
+            OP_SUB_F32 => Inst::sub_f32,
+            OP_SUB_F64 => Inst::sub_f64,
+            OP_MUL_F32 => Inst::mul_f32,
+            OP_MUL_F64 => Inst::mul_f64,
+            OP_DIV_F32 => Inst::div_f32,
+            OP_DIV_F64 => Inst::div_f64,
+            OP_LDGLOBAL => { let idx = read_u16(bytes, &mut pos)?; Inst::ldglobal(idx) }
+            OP_STGLOBAL => { let idx = read_u16(bytes, &mut pos)?; Inst::stglobal(idx) }
+            OP_ADD_I64 => Inst::add_i64,
+            OP_SUB_I64 => Inst::sub_i64,
+            OP_MUL_I64 => Inst::mul_i64,
+            OP_DIV_I64 => Inst::div_i64,
+            op => return Err(BinaryError::UnknownOpcode(op)),
+        };
+        body.push(inst);
+    }
+    Ok(body)
+}
+
+fn read_u8(bytes: &[u8], pos: &mut usize) -> Result<u8, BinaryError> {
+    let value = *bytes.get(*pos).ok_or(BinaryError::UnexpectedEof)?;
+    *pos += 1;
+    Ok(value)
+}
+
+fn read_u16(bytes: &[u8], pos: &mut usize) -> Result<u16, BinaryError> {
+    Ok(u16::from_le_bytes([read_u8(bytes, pos)?, read_u8(bytes, pos)?]))
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, BinaryError> {
+    let mut buf = [0u8; 4];
+    for byte in buf.iter_mut() {
+        *byte = read_u8(bytes, pos)?;
+    }
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(bytes: &[u8], pos: &mut usize) -> Result<u64, BinaryError> {
+    let mut buf = [0u8; 8];
+    for byte in buf.iter_mut() {
+        *byte = read_u8(bytes, pos)?;
+    }
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> Result<String, BinaryError> {
+    let len = read_u16(bytes, pos)? as usize;
+    let end = pos.checked_add(len).filter(|&end| end <= bytes.len()).ok_or(BinaryError::UnexpectedEof)?;
+    let s = String::from_utf8(bytes[*pos..end].to_vec()).map_err(|_| BinaryError::InvalidUtf8)?;
+    *pos = end;
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_the_binary_format() {
+        let main = ManagedFuncDef {
+            name: "main".into(),
+            args: 0,
+            returns: true,
+            default_locals: vec![Value::I32(0), Value::I64(9), Value::F32(1.5), Value::F64(2.5)],
+            body: vec![
+                Inst::ldc_i32(42),
+                Inst::ldc_i64(99),
+                Inst::add_i64,
+                Inst::ldglobal(0),
+                Inst::stglobal(1),
+                Inst::try_(1),
+                Inst::ret,
+            ],
+        };
+        let asm = Assembly {
+            entry: 0,
+            name: "test.asm".into(),
+            functions: vec![FuncDef::Managed(main), FuncDef::Native(NativeFuncDef { name: "random".into(), args: 0, returns: true })],
+            globals: vec![1, 2, 3],
+        };
+
+        let bytes = write_binary(&asm);
+        let round_tripped = disassemble(&bytes).unwrap();
+
+        assert_eq!(round_tripped.entry, asm.entry);
+        assert_eq!(round_tripped.name, asm.name);
+        assert_eq!(round_tripped.globals, asm.globals);
+        assert_eq!(round_tripped.functions.len(), asm.functions.len());
+
+        let original = asm.functions[0].as_managed().unwrap();
+        let decoded = round_tripped.functions[0].as_managed().unwrap();
+        assert_eq!(decoded.name, original.name);
+        assert_eq!(decoded.args, original.args);
+        assert_eq!(decoded.returns, original.returns);
+        assert_eq!(
+            decoded.default_locals.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            original.default_locals.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            decoded.body.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            original.body.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+
+        assert_eq!(round_tripped.functions[1].name(), "random");
+    }
+
+    #[test]
+    fn disassemble_rejects_a_version_mismatch() {
+        let mut bytes = write_binary(&Assembly { entry: 0, name: "test".into(), functions: vec![], globals: vec![] });
+        bytes[4] = 0xff;
+        bytes[5] = 0xff;
+        match disassemble(&bytes) {
+            Err(BinaryError::UnsupportedVersion(0xffff)) => (),
+            Err(other) => panic!("expected BinaryError::UnsupportedVersion, got {:?}", other),
+            Ok(_) => panic!("expected disassemble to reject a version mismatch"),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use ::std::fmt;
+use super::value::Value;
+
+#[derive(Debug)]
+pub enum VmError {
+    StackUnderflow,
+    DivideByZero,
+    LocalOutOfBounds,
+    UndefinedNative(String),
+    CallStackOverflow,
+    ValueStackOverflow,
+    TypeMismatch,
+    UncaughtException(Value),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            VmError::StackUnderflow => write!(f, "operand stack underflow"),
+            VmError::DivideByZero => write!(f, "division by zero"),
+            VmError::LocalOutOfBounds => write!(f, "local variable index out of bounds"),
+            VmError::UndefinedNative(name) => write!(f, "calling undefined native function: {}", name),
+            VmError::CallStackOverflow => write!(f, "call stack overflow"),
+            VmError::ValueStackOverflow => write!(f, "value stack overflow"),
+            VmError::TypeMismatch => write!(f, "operand type mismatch"),
+            VmError::UncaughtException(exception) => write!(f, "uncaught exception: {}", exception),
+        }
+    }
+}
@@ -13,11 +13,45 @@ pub enum Inst {
     mul_s,
     div_u,
     div_s,
+    mod_u,
+    mod_s,
+    shl,
+    shr_u,
+    shr_s,
+    and,
+    or,
+    xor,
+    cmp_eq,
+    cmp_lt_u,
+    cmp_lt_s,
+    cmp_gt_u,
+    cmp_gt_s,
     jump(u32),
     beq(u32),
     breakpoint,
     call(u16),
     ret,
+    try_(u32),
+    endtry,
+    throw,
+    ldc_i32(u32),
+    ldc_i64(u64),
+    ldc_f32(f32),
+    ldc_f64(f64),
+    add_f32,
+    add_f64,
+    sub_f32,
+    sub_f64,
+    mul_f32,
+    mul_f64,
+    div_f32,
+    div_f64,
+    add_i64,
+    sub_i64,
+    mul_i64,
+    div_i64,
+    ldglobal(u16),
+    stglobal(u16),
 }
 
 impl fmt::Display for Inst {
@@ -31,6 +65,19 @@ impl fmt::Display for Inst {
             &Inst::mul_s => write!(f, "mul.s")?,
             &Inst::div_u => write!(f, "div.u")?,
             &Inst::div_s => write!(f, "div.s")?,
+            &Inst::mod_u => write!(f, "mod.u")?,
+            &Inst::mod_s => write!(f, "mod.s")?,
+            &Inst::shl => write!(f, "shl")?,
+            &Inst::shr_u => write!(f, "shr.u")?,
+            &Inst::shr_s => write!(f, "shr.s")?,
+            &Inst::and => write!(f, "and")?,
+            &Inst::or => write!(f, "or")?,
+            &Inst::xor => write!(f, "xor")?,
+            &Inst::cmp_eq => write!(f, "cmp.eq")?,
+            &Inst::cmp_lt_u => write!(f, "cmp.lt.u")?,
+            &Inst::cmp_lt_s => write!(f, "cmp.lt.s")?,
+            &Inst::cmp_gt_u => write!(f, "cmp.gt.u")?,
+            &Inst::cmp_gt_s => write!(f, "cmp.gt.s")?,
             &Inst::jump(dst) => write!(f, "jump {}", dst)?,
             &Inst::beq(dst) => write!(f, "beq {}", dst)?,
             &Inst::ldarg(idx) => write!(f, "ldarg {}", idx)?,
@@ -38,6 +85,27 @@ impl fmt::Display for Inst {
             &Inst::breakpoint => write!(f, "breakpoint")?,
             &Inst::ret => write!(f, "ret")?,
             &Inst::call(idx) => write!(f, "call {}", idx)?,
+            &Inst::try_(dst) => write!(f, "try {}", dst)?,
+            &Inst::endtry => write!(f, "endtry")?,
+            &Inst::throw => write!(f, "throw")?,
+            &Inst::ldc_i32(val) => write!(f, "ldc.i32 {}", val)?,
+            &Inst::ldc_i64(val) => write!(f, "ldc.i64 {}", val)?,
+            &Inst::ldc_f32(val) => write!(f, "ldc.f32 {}", val)?,
+            &Inst::ldc_f64(val) => write!(f, "ldc.f64 {}", val)?,
+            &Inst::add_f32 => write!(f, "add.f32")?,
+            &Inst::add_f64 => write!(f, "add.f64")?,
+            &Inst::sub_f32 => write!(f, "sub.f32")?,
+            &Inst::sub_f64 => write!(f, "sub.f64")?,
+            &Inst::mul_f32 => write!(f, "mul.f32")?,
+            &Inst::mul_f64 => write!(f, "mul.f64")?,
+            &Inst::div_f32 => write!(f, "div.f32")?,
+            &Inst::div_f64 => write!(f, "div.f64")?,
+            &Inst::add_i64 => write!(f, "add.i64")?,
+            &Inst::sub_i64 => write!(f, "sub.i64")?,
+            &Inst::mul_i64 => write!(f, "mul.i64")?,
+            &Inst::div_i64 => write!(f, "div.i64")?,
+            &Inst::ldglobal(idx) => write!(f, "ldglobal {}", idx)?,
+            &Inst::stglobal(idx) => write!(f, "stglobal {}", idx)?,
         };
         Ok(())
     }
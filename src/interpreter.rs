@@ -1,11 +1,65 @@
+use ::std::collections::HashMap;
 use super::instructions::Inst;
 use super::assembly::{Assembly, FuncDef, ManagedFuncDef, NativeFuncDef};
+use super::error::VmError;
+use super::value::Value;
 use super::rand;
 
+/// Limits the VM enforces on otherwise-unbounded growth, so a malformed or
+/// runaway program reports a `VmError` instead of exhausting host memory.
+pub struct VmConfig {
+    pub max_call_depth: usize,
+    pub max_value_stack: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            max_call_depth: 16 * 1024,
+            max_value_stack: 1024 * 1024,
+        }
+    }
+}
+
+/// Host functions callable from `call`. A native receives the caller's
+/// operand stack so it can pop its own arguments and, if `returns` is set,
+/// is expected to leave nothing on it but hand back its result.
+pub struct NativeRegistry {
+    natives: HashMap<String, Box<dyn Fn(&mut Vec<Value>) -> Option<Value>>>,
+}
+
+impl NativeRegistry {
+    /// Registers a host function under `name`, overriding any existing one.
+    /// Embedders use this to expose I/O, math, time, etc. to managed code.
+    pub fn register_native<F>(&mut self, name: &str, native: F)
+        where F: Fn(&mut Vec<Value>) -> Option<Value> + 'static
+    {
+        self.natives.insert(name.to_owned(), Box::new(native));
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn Fn(&mut Vec<Value>) -> Option<Value>> {
+        self.natives.get(name).map(|native| native.as_ref())
+    }
+}
+
+impl Default for NativeRegistry {
+    fn default() -> Self {
+        let mut registry = NativeRegistry { natives: HashMap::new() };
+        registry.register_native("random", |_stack| Some(Value::I32(rand::random())));
+        registry
+    }
+}
+
+struct TryFrame {
+    handler_pc: u32,
+    stack_len: usize,
+}
+
 struct ManagedCallFrame {
     program_counter: u32,
-    stack: Vec<u32>,
-    locals: Vec<u32>,
+    stack: Vec<Value>,
+    locals: Vec<Value>,
+    try_frames: Vec<TryFrame>,
 }
 
 impl ManagedCallFrame {
@@ -13,20 +67,22 @@ impl ManagedCallFrame {
         ManagedCallFrame::with_locals(def.default_locals.clone())
     }
 
-    fn with_locals(locals: Vec<u32>) -> Self {
+    fn with_locals(locals: Vec<Value>) -> Self {
         ManagedCallFrame {
             program_counter: 0,
             stack: vec![],
             locals,
+            try_frames: vec![],
         }
     }
 
-    fn create_frame_for_callee(&mut self, callee: &ManagedFuncDef) -> ManagedCallFrame {
+    fn create_frame_for_callee(&mut self, callee: &ManagedFuncDef) -> Result<ManagedCallFrame, VmError> {
         let mut locals = callee.default_locals.clone();
         for idx in 0..callee.args {
-            locals[idx as usize] = self.stack.pop().unwrap();
+            let local = locals.get_mut(idx as usize).ok_or(VmError::LocalOutOfBounds)?;
+            *local = self.stack.pop().ok_or(VmError::StackUnderflow)?;
         }
-        Self::with_locals(locals)
+        Ok(Self::with_locals(locals))
     }
 }
 
@@ -49,25 +105,47 @@ enum ExecutionStatus {
     Return,
     Normal,
     Breakpoint,
+    Throw(Value),
+}
+
+enum FrameOutcome<'a> {
+    Call(CallFrame<'a>),
+    Return,
+    Throw(Value),
+}
+
+pub fn execute_assembly(asm: &Assembly) -> Result<Vec<Value>, VmError> {
+    execute_assembly_with(asm, &VmConfig::default(), &NativeRegistry::default())
+}
+
+pub fn execute_assembly_with_config(asm: &Assembly, config: &VmConfig) -> Result<Vec<Value>, VmError> {
+    execute_assembly_with(asm, config, &NativeRegistry::default())
 }
 
-pub fn execute_assembly(asm: &Assembly) {
+/// Runs `asm` against a caller-supplied native registry, so an embedder can
+/// expose its own host functions instead of just the built-in ones.
+pub fn execute_assembly_with(asm: &Assembly, config: &VmConfig, natives: &NativeRegistry) -> Result<Vec<Value>, VmError> {
     let entry = asm.get_entry().as_managed().unwrap();
     let mut call_stack = vec![
         CallFrame::Managed(entry, ManagedCallFrame::by_func(entry))
     ];
+    let mut globals = asm.globals.clone();
+    let mut results = vec![];
     while !call_stack.is_empty() {
-        let callee_frame = match call_stack.last_mut().unwrap() {
+        let outcome = match call_stack.last_mut().unwrap() {
             CallFrame::Managed(callee, ref mut caller_frame) => {
-                run_managed_until_call(&asm, &callee, caller_frame)
+                run_managed_until_call(&asm, &callee, caller_frame, config, &mut globals)?
             }
             CallFrame::Native(_callee) => {
-                None
+                FrameOutcome::Return
             },
         };
 
-        match callee_frame {
-            Some(callee_frame) => {
+        match outcome {
+            FrameOutcome::Call(callee_frame) => {
+                if call_stack.len() >= config.max_call_depth {
+                    return Err(VmError::CallStackOverflow);
+                }
                 match call_stack.last().unwrap() {
                     CallFrame::Managed(caller, _caller_frame) => {
                         eprintln!("Calling '{}' from '{}'", callee_frame.name(), caller.name);
@@ -76,141 +154,603 @@ pub fn execute_assembly(asm: &Assembly) {
                 }
                 call_stack.push(callee_frame);
             }
-            None => match call_stack.pop().unwrap() {
+            FrameOutcome::Return => match call_stack.pop().unwrap() {
                 CallFrame::Managed(callee, callee_frame) => {
-                    finish_managed_call(&mut call_stack, callee, callee_frame)
+                    if let Some(result) = finish_managed_call(&mut call_stack, callee, callee_frame, config)? {
+                        if call_stack.is_empty() {
+                            results.push(result);
+                        }
+                    }
                 }
                 CallFrame::Native(callee) => {
-                    finish_native_call(&mut call_stack, callee)
+                    finish_native_call(&mut call_stack, callee, natives, config)?
                 },
             },
+            FrameOutcome::Throw(exception) => {
+                call_stack.pop();
+                if !unwind_to_handler(&mut call_stack, exception) {
+                    return Err(VmError::UncaughtException(exception));
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+// Walks the call stack outward from the frame that just threw, discarding
+// frames until one with a matching `try` handler is found. Returns false if
+// the exception reaches the bottom of the stack unhandled.
+fn unwind_to_handler(call_stack: &mut Vec<CallFrame>, exception: Value) -> bool {
+    while let Some(frame) = call_stack.last_mut() {
+        if let CallFrame::Managed(_, ref mut frame_state) = frame {
+            if let Some(handler) = frame_state.try_frames.pop() {
+                frame_state.stack.truncate(handler.stack_len);
+                frame_state.stack.push(exception);
+                frame_state.program_counter = handler.handler_pc;
+                return true;
+            }
         }
+        call_stack.pop();
     }
+    false
 }
 
-fn finish_managed_call(call_stack: &mut Vec<CallFrame>, callee: &ManagedFuncDef, callee_frame: ManagedCallFrame) {
+fn finish_managed_call(call_stack: &mut Vec<CallFrame>, callee: &ManagedFuncDef, callee_frame: ManagedCallFrame, config: &VmConfig) -> Result<Option<Value>, VmError> {
     if callee.returns {
-        let result = callee_frame.locals[callee.args as usize];
+        let result = *callee_frame.locals.get(callee.args as usize).ok_or(VmError::LocalOutOfBounds)?;
         if let Some(frame) = call_stack.last_mut() {
             match frame {
                 CallFrame::Managed(_, caller_frame) => {
-                    caller_frame.stack.push(result);
+                    push_value(caller_frame, result, config)?;
                 }
                 CallFrame::Native(_) => unimplemented!(),
             }
         }
         eprintln!("Returning from '{}' with result '{}'", callee.name, result);
+        Ok(Some(result))
     } else {
         eprintln!("Returning from '{}'", callee.name);
+        Ok(None)
     }
 }
 
-fn finish_native_call(call_stack: &mut Vec<CallFrame>, callee: &NativeFuncDef) {
-    let result = match &callee.name[..] {
-        "random" => Some(rand::random()),
-        name => panic!("Calling undefined function: {}", name),
-    };
+fn finish_native_call(call_stack: &mut Vec<CallFrame>, callee: &NativeFuncDef, natives: &NativeRegistry, config: &VmConfig) -> Result<(), VmError> {
+    let native = natives.get(&callee.name).ok_or_else(|| VmError::UndefinedNative(callee.name.clone()))?;
 
-    if callee.returns {
-        let result = result.expect("Native function didn't return anything");
-        if let Some(frame) = call_stack.last_mut() {
-            match frame {
-                CallFrame::Managed(_, ref mut caller_frame) => {
-                    caller_frame.stack.push(result);
+    if let Some(frame) = call_stack.last_mut() {
+        match frame {
+            CallFrame::Managed(_, ref mut caller_frame) => {
+                let result = native(&mut caller_frame.stack);
+                if callee.returns {
+                    push_value(caller_frame, result.expect("Native function didn't return anything"), config)?;
+                } else {
+                    eprintln!("Returning from '{}'", callee.name);
                 }
-                CallFrame::Native(_) => unimplemented!(),
             }
+            CallFrame::Native(_) => unimplemented!(),
         }
-    } else {
-        eprintln!("Returning from '{}'", callee.name);
     }
+    Ok(())
 }
 
 fn run_managed_until_call<'a>(
     asm: &'a Assembly,
     callee: &ManagedFuncDef,
-    caller_frame: &mut ManagedCallFrame
-) -> Option<CallFrame<'a>> {
+    caller_frame: &mut ManagedCallFrame,
+    config: &VmConfig,
+    globals: &mut Vec<u32>,
+) -> Result<FrameOutcome<'a>, VmError> {
     loop {
-        match step_managed(callee, caller_frame) {
+        match step_managed(callee, caller_frame, config, globals)? {
             ExecutionStatus::Normal => (),
             ExecutionStatus::Call(callee_idx) => {
                 let callee = &asm.functions[callee_idx as usize];
                 let callee_frame = match callee {
                     FuncDef::Managed(ref callee) => {
-                        CallFrame::Managed(callee, caller_frame.create_frame_for_callee(callee))
+                        CallFrame::Managed(callee, caller_frame.create_frame_for_callee(callee)?)
                     },
                     FuncDef::Native(ref callee) => {
                         CallFrame::Native(callee)
                     }
                 };
-                break Some(callee_frame)
+                break Ok(FrameOutcome::Call(callee_frame))
             }
-            ExecutionStatus::Return => break None,
+            ExecutionStatus::Return => break Ok(FrameOutcome::Return),
+            ExecutionStatus::Throw(exception) => break Ok(FrameOutcome::Throw(exception)),
             ExecutionStatus::Breakpoint => print_managed_debug_info(callee, caller_frame),
         }
     }
 }
 
-use num::cast::{FromPrimitive, ToPrimitive};
+/// Reinterprets the raw bits the operand stack stores a 32-bit value as,
+/// in either direction. Signed and unsigned 32-bit operands share the same
+/// `Value::I32(u32)` storage, so converting between them needs a bit-level
+/// cast - the value-preserving conversions `FromPrimitive`/`ToPrimitive`
+/// provide are the wrong tool: `from_u32`/`to_u32` reject any bit pattern
+/// that isn't a non-negative `i32`, e.g. every negative signed operand or
+/// result.
+trait Bits32 {
+    fn from_bits32(bits: u32) -> Self;
+    fn to_bits32(self) -> u32;
+}
+
+impl Bits32 for u32 {
+    fn from_bits32(bits: u32) -> Self { bits }
+    fn to_bits32(self) -> u32 { self }
+}
+
+impl Bits32 for i32 {
+    fn from_bits32(bits: u32) -> Self { bits as i32 }
+    fn to_bits32(self) -> u32 { self as u32 }
+}
+
+fn pop_value(frame: &mut ManagedCallFrame) -> Result<Value, VmError> {
+    frame.stack.pop().ok_or(VmError::StackUnderflow)
+}
+
+fn push_value(frame: &mut ManagedCallFrame, value: Value, config: &VmConfig) -> Result<(), VmError> {
+    if frame.stack.len() >= config.max_value_stack {
+        return Err(VmError::ValueStackOverflow);
+    }
+    frame.stack.push(value);
+    Ok(())
+}
+
+fn expect_i32(value: Value) -> Result<u32, VmError> {
+    match value {
+        Value::I32(v) => Ok(v),
+        _ => Err(VmError::TypeMismatch),
+    }
+}
+
+fn pop_typed<T: Bits32>(frame: &mut ManagedCallFrame) -> Result<T, VmError> {
+    let raw = expect_i32(pop_value(frame)?)?;
+    Ok(T::from_bits32(raw))
+}
 
 #[inline(always)]
-fn binary<T>(frame: &mut ManagedCallFrame, operator: fn(T, T) -> T)
-    where T: ToPrimitive + FromPrimitive
+fn binary<T>(frame: &mut ManagedCallFrame, config: &VmConfig, operator: fn(T, T) -> T) -> Result<(), VmError>
+    where T: Bits32
 {
-    let value2 = frame.stack.pop().and_then(FromPrimitive::from_u32).unwrap();
-    let value1 = frame.stack.pop().and_then(FromPrimitive::from_u32).unwrap();
-    let result = operator(value2, value1).to_u32().unwrap();
-    frame.stack.push(result);
+    let value2: T = pop_typed(frame)?;
+    let value1: T = pop_typed(frame)?;
+    let result = operator(value2, value1).to_bits32();
+    push_value(frame, Value::I32(result), config)
 }
 
-fn step_managed(function: &ManagedFuncDef, frame: &mut ManagedCallFrame) -> ExecutionStatus {
+#[inline(always)]
+fn compare<T>(frame: &mut ManagedCallFrame, config: &VmConfig, operator: fn(T, T) -> bool) -> Result<(), VmError>
+    where T: Bits32
+{
+    let value2: T = pop_typed(frame)?;
+    let value1: T = pop_typed(frame)?;
+    push_value(frame, Value::I32(if operator(value2, value1) { 1 } else { 0 }), config)
+}
+
+#[inline(always)]
+fn binary_f32(frame: &mut ManagedCallFrame, config: &VmConfig, operator: fn(f32, f32) -> f32) -> Result<(), VmError> {
+    let value2 = pop_value(frame)?;
+    let value1 = pop_value(frame)?;
+    match (value2, value1) {
+        (Value::F32(a), Value::F32(b)) => push_value(frame, Value::F32(operator(a, b)), config),
+        _ => Err(VmError::TypeMismatch),
+    }
+}
+
+#[inline(always)]
+fn binary_f64(frame: &mut ManagedCallFrame, config: &VmConfig, operator: fn(f64, f64) -> f64) -> Result<(), VmError> {
+    let value2 = pop_value(frame)?;
+    let value1 = pop_value(frame)?;
+    match (value2, value1) {
+        (Value::F64(a), Value::F64(b)) => push_value(frame, Value::F64(operator(a, b)), config),
+        _ => Err(VmError::TypeMismatch),
+    }
+}
+
+#[inline(always)]
+fn binary_i64(frame: &mut ManagedCallFrame, config: &VmConfig, operator: fn(i64, i64) -> i64) -> Result<(), VmError> {
+    let value2 = pop_value(frame)?;
+    let value1 = pop_value(frame)?;
+    match (value2, value1) {
+        (Value::I64(a), Value::I64(b)) => push_value(frame, Value::I64(operator(a as i64, b as i64) as u64), config),
+        _ => Err(VmError::TypeMismatch),
+    }
+}
+
+fn step_managed(function: &ManagedFuncDef, frame: &mut ManagedCallFrame, config: &VmConfig, globals: &mut Vec<u32>) -> Result<ExecutionStatus, VmError> {
     if frame.program_counter as usize >= function.body.len() {
-        return ExecutionStatus::Return;
+        return Ok(ExecutionStatus::Return);
     }
     match function.body[frame.program_counter as usize] {
-        Inst::add_u => binary::<u32>(frame, |a, b| a + b),
-        Inst::add_s => binary::<i32>(frame, |a, b| a + b),
-        Inst::sub_u => binary::<u32>(frame, |a, b| a - b),
-        Inst::sub_s => binary::<i32>(frame, |a, b| a - b),
-        Inst::mul_u => binary::<u32>(frame, |a, b| a * b),
-        Inst::mul_s => binary::<i32>(frame, |a, b| a * b),
-        Inst::div_u => binary::<u32>(frame, |a, b| a / b),
-        Inst::div_s => binary::<i32>(frame, |a, b| a / b),
+        Inst::add_u => binary::<u32>(frame, config, |a, b| a + b)?,
+        Inst::add_s => binary::<i32>(frame, config, |a, b| a + b)?,
+        Inst::sub_u => binary::<u32>(frame, config, |a, b| a - b)?,
+        Inst::sub_s => binary::<i32>(frame, config, |a, b| a - b)?,
+        Inst::mul_u => binary::<u32>(frame, config, |a, b| a * b)?,
+        Inst::mul_s => binary::<i32>(frame, config, |a, b| a * b)?,
+        Inst::div_u => {
+            let value2: u32 = pop_typed(frame)?;
+            let value1: u32 = pop_typed(frame)?;
+            if value1 == 0 {
+                return Err(VmError::DivideByZero);
+            }
+            push_value(frame, Value::I32(value2 / value1), config)?;
+        }
+        Inst::div_s => {
+            let value2: i32 = pop_typed(frame)?;
+            let value1: i32 = pop_typed(frame)?;
+            if value1 == 0 {
+                return Err(VmError::DivideByZero);
+            }
+            push_value(frame, Value::I32((value2 / value1) as u32), config)?;
+        }
+        Inst::mod_u => {
+            let value2: u32 = pop_typed(frame)?;
+            let value1: u32 = pop_typed(frame)?;
+            if value1 == 0 {
+                return Err(VmError::DivideByZero);
+            }
+            push_value(frame, Value::I32(value2 % value1), config)?;
+        }
+        Inst::mod_s => {
+            let value2: i32 = pop_typed(frame)?;
+            let value1: i32 = pop_typed(frame)?;
+            if value1 == 0 {
+                return Err(VmError::DivideByZero);
+            }
+            push_value(frame, Value::I32((value2 % value1) as u32), config)?;
+        }
+        Inst::shl => binary::<u32>(frame, config, |a, b| a << (b & 31))?,
+        Inst::shr_u => binary::<u32>(frame, config, |a, b| a >> (b & 31))?,
+        Inst::shr_s => binary::<i32>(frame, config, |a, b| a >> (b & 31))?,
+        Inst::and => binary::<u32>(frame, config, |a, b| a & b)?,
+        Inst::or => binary::<u32>(frame, config, |a, b| a | b)?,
+        Inst::xor => binary::<u32>(frame, config, |a, b| a ^ b)?,
+        Inst::cmp_eq => compare::<u32>(frame, config, |a, b| a == b)?,
+        Inst::cmp_lt_u => compare::<u32>(frame, config, |a, b| a < b)?,
+        Inst::cmp_lt_s => compare::<i32>(frame, config, |a, b| a < b)?,
+        Inst::cmp_gt_u => compare::<u32>(frame, config, |a, b| a > b)?,
+        Inst::cmp_gt_s => compare::<i32>(frame, config, |a, b| a > b)?,
+        Inst::ldc_i32(val) => push_value(frame, Value::I32(val), config)?,
+        Inst::ldc_i64(val) => push_value(frame, Value::I64(val), config)?,
+        Inst::ldc_f32(val) => push_value(frame, Value::F32(val), config)?,
+        Inst::ldc_f64(val) => push_value(frame, Value::F64(val), config)?,
+        Inst::add_f32 => binary_f32(frame, config, |a, b| a + b)?,
+        Inst::add_f64 => binary_f64(frame, config, |a, b| a + b)?,
+        Inst::sub_f32 => binary_f32(frame, config, |a, b| a - b)?,
+        Inst::sub_f64 => binary_f64(frame, config, |a, b| a - b)?,
+        Inst::mul_f32 => binary_f32(frame, config, |a, b| a * b)?,
+        Inst::mul_f64 => binary_f64(frame, config, |a, b| a * b)?,
+        Inst::div_f32 => binary_f32(frame, config, |a, b| a / b)?,
+        Inst::div_f64 => binary_f64(frame, config, |a, b| a / b)?,
+        Inst::add_i64 => binary_i64(frame, config, |a, b| a + b)?,
+        Inst::sub_i64 => binary_i64(frame, config, |a, b| a - b)?,
+        Inst::mul_i64 => binary_i64(frame, config, |a, b| a * b)?,
+        Inst::div_i64 => {
+            let value2 = pop_value(frame)?;
+            let value1 = pop_value(frame)?;
+            let (value2, value1) = match (value2, value1) {
+                (Value::I64(a), Value::I64(b)) => (a as i64, b as i64),
+                _ => return Err(VmError::TypeMismatch),
+            };
+            if value1 == 0 {
+                return Err(VmError::DivideByZero);
+            }
+            push_value(frame, Value::I64((value2 / value1) as u64), config)?;
+        }
+        Inst::ldglobal(idx) => {
+            let value = *globals.get(idx as usize).ok_or(VmError::LocalOutOfBounds)?;
+            push_value(frame, Value::I32(value), config)?;
+        }
+        Inst::stglobal(idx) => {
+            let value = expect_i32(pop_value(frame)?)?;
+            let slot = globals.get_mut(idx as usize).ok_or(VmError::LocalOutOfBounds)?;
+            *slot = value;
+        }
         Inst::jump(target) => {
             frame.program_counter = target;
-            return ExecutionStatus::Normal;
+            return Ok(ExecutionStatus::Normal);
         }
         Inst::beq(target) => {
-            let value2 = frame.stack.pop().unwrap();
-            let value1 = frame.stack.pop().unwrap();
+            let value2 = pop_value(frame)?;
+            let value1 = pop_value(frame)?;
             if value1 == value2 {
                 frame.program_counter = target;
-                return ExecutionStatus::Normal;
+                return Ok(ExecutionStatus::Normal);
             }
         }
         Inst::ldarg(n) => {
-            let value = frame.locals[n as usize];
-            frame.stack.push(value);
+            let value = *frame.locals.get(n as usize).ok_or(VmError::LocalOutOfBounds)?;
+            push_value(frame, value, config)?;
         }
         Inst::starg(n) => {
-            frame.locals[n as usize] = frame.stack.pop().unwrap();
+            let value = pop_value(frame)?;
+            let local = frame.locals.get_mut(n as usize).ok_or(VmError::LocalOutOfBounds)?;
+            *local = value;
         }
         Inst::call(idx) => {
             frame.program_counter += 1;
-            return ExecutionStatus::Call(idx);
+            return Ok(ExecutionStatus::Call(idx));
         }
         Inst::ret => {
             frame.program_counter += 1;
-            return ExecutionStatus::Return;
+            return Ok(ExecutionStatus::Return);
         }
         Inst::breakpoint => {
             frame.program_counter += 1;
-            return ExecutionStatus::Breakpoint;
+            return Ok(ExecutionStatus::Breakpoint);
+        }
+        Inst::try_(handler_pc) => {
+            frame.try_frames.push(TryFrame { handler_pc, stack_len: frame.stack.len() });
+        }
+        Inst::endtry => {
+            frame.try_frames.pop();
+        }
+        Inst::throw => {
+            let exception = pop_value(frame)?;
+            if let Some(handler) = frame.try_frames.pop() {
+                frame.stack.truncate(handler.stack_len);
+                frame.stack.push(exception);
+                frame.program_counter = handler.handler_pc;
+                return Ok(ExecutionStatus::Normal);
+            }
+            return Ok(ExecutionStatus::Throw(exception));
         }
     }
     frame.program_counter += 1;
-    ExecutionStatus::Normal
+    Ok(ExecutionStatus::Normal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn managed(args: u16, returns: bool, default_locals: Vec<Value>, body: Vec<Inst>) -> ManagedFuncDef {
+        ManagedFuncDef { name: "test".into(), args, returns, default_locals, body }
+    }
+
+    fn run(mut body: Vec<Inst>) -> Value {
+        body.push(Inst::starg(0));
+        body.push(Inst::ret);
+        let main = managed(0, true, vec![Value::I32(0)], body);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+        execute_assembly(&asm).unwrap().remove(0)
+    }
+
+    #[test]
+    fn catches_exception_in_same_frame() {
+        let main = managed(0, true, vec![Value::I32(0)], vec![
+            Inst::try_(3),
+            Inst::ldc_i32(99),
+            Inst::throw,
+            Inst::starg(0),
+            Inst::ret,
+        ]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+
+        assert_eq!(execute_assembly(&asm).unwrap(), vec![Value::I32(99)]);
+    }
+
+    #[test]
+    fn propagates_uncaught_throw_to_callers_handler() {
+        let caller = managed(0, true, vec![Value::I32(0)], vec![
+            Inst::try_(3),
+            Inst::call(1),
+            Inst::ret,
+            Inst::starg(0),
+            Inst::ret,
+        ]);
+        let callee = managed(0, false, vec![], vec![
+            Inst::ldc_i32(77),
+            Inst::throw,
+        ]);
+        let asm = Assembly {
+            entry: 0,
+            name: "test".into(),
+            functions: vec![FuncDef::Managed(caller), FuncDef::Managed(callee)],
+            globals: vec![],
+        };
+
+        assert_eq!(execute_assembly(&asm).unwrap(), vec![Value::I32(77)]);
+    }
+
+    #[test]
+    fn signed_subtraction_can_produce_a_negative_result() {
+        let main = managed(0, true, vec![Value::I32(0)], vec![
+            Inst::ldc_i32(5),
+            Inst::ldc_i32(3),
+            Inst::sub_s,
+            Inst::starg(0),
+            Inst::ret,
+        ]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+
+        assert_eq!(execute_assembly(&asm).unwrap(), vec![Value::I32((-2i32) as u32)]);
+    }
+
+    #[test]
+    fn shift_count_past_bit_width_is_masked_instead_of_panicking() {
+        let result = run(vec![Inst::ldc_i32(35), Inst::ldc_i32(1), Inst::shl]);
+        assert_eq!(result, Value::I32(1 << (35u32 & 31)));
+    }
+
+    #[test]
+    fn signed_add_with_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32((-5i32) as u32), Inst::ldc_i32(3), Inst::add_s]);
+        assert_eq!(result, Value::I32((-2i32) as u32));
+    }
+
+    #[test]
+    fn signed_mul_with_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32((-3i32) as u32), Inst::ldc_i32(4), Inst::mul_s]);
+        assert_eq!(result, Value::I32((-12i32) as u32));
+    }
+
+    #[test]
+    fn signed_div_with_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32(3), Inst::ldc_i32((-6i32) as u32), Inst::div_s]);
+        assert_eq!(result, Value::I32((-2i32) as u32));
+    }
+
+    #[test]
+    fn signed_mod_with_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32(3), Inst::ldc_i32((-7i32) as u32), Inst::mod_s]);
+        assert_eq!(result, Value::I32((-1i32) as u32));
+    }
+
+    #[test]
+    fn signed_less_than_with_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32(3), Inst::ldc_i32((-5i32) as u32), Inst::cmp_lt_s]);
+        assert_eq!(result, Value::I32(1));
+    }
+
+    #[test]
+    fn signed_greater_than_with_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32((-5i32) as u32), Inst::ldc_i32(3), Inst::cmp_gt_s]);
+        assert_eq!(result, Value::I32(1));
+    }
+
+    #[test]
+    fn signed_right_shift_sign_extends_a_negative_operand() {
+        let result = run(vec![Inst::ldc_i32(1), Inst::ldc_i32((-8i32) as u32), Inst::shr_s]);
+        assert_eq!(result, Value::I32((-4i32) as u32));
+    }
+
+    #[test]
+    fn i64_arithmetic_computes_across_the_family() {
+        let result = run(vec![Inst::ldc_i64(3), Inst::ldc_i64(4), Inst::add_i64]);
+        assert_eq!(result, Value::I64(7));
+
+        let result = run(vec![Inst::ldc_i64(10), Inst::ldc_i64(4), Inst::mul_i64]);
+        assert_eq!(result, Value::I64(40));
+    }
+
+    #[test]
+    fn i64_subtraction_can_produce_a_negative_result() {
+        let result = run(vec![Inst::ldc_i64(8), Inst::ldc_i64(5), Inst::sub_i64]);
+        assert_eq!(result, Value::I64((-3i64) as u64));
+    }
+
+    #[test]
+    fn i64_division_by_zero_is_a_vm_error() {
+        let main = managed(0, false, vec![], vec![
+            Inst::ldc_i64(0),
+            Inst::ldc_i64(5),
+            Inst::div_i64,
+        ]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+
+        match execute_assembly(&asm) {
+            Err(VmError::DivideByZero) => (),
+            other => panic!("expected VmError::DivideByZero, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn globals_round_trip_through_ldglobal_and_stglobal() {
+        let main = managed(0, true, vec![Value::I32(0)], vec![
+            Inst::ldc_i32(42),
+            Inst::stglobal(0),
+            Inst::ldglobal(0),
+            Inst::starg(0),
+            Inst::ret,
+        ]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![0] };
+
+        assert_eq!(execute_assembly(&asm).unwrap(), vec![Value::I32(42)]);
+    }
+
+    #[test]
+    fn calls_a_registered_native_function() {
+        let native = NativeFuncDef { name: "double".into(), args: 1, returns: true };
+        let main = managed(0, true, vec![Value::I32(0)], vec![
+            Inst::ldc_i32(21),
+            Inst::call(1),
+            Inst::starg(0),
+            Inst::ret,
+        ]);
+        let asm = Assembly {
+            entry: 0,
+            name: "test".into(),
+            functions: vec![FuncDef::Managed(main), FuncDef::Native(native)],
+            globals: vec![],
+        };
+
+        let mut natives = NativeRegistry::default();
+        natives.register_native("double", |stack| match stack.pop() {
+            Some(Value::I32(v)) => Some(Value::I32(v * 2)),
+            _ => None,
+        });
+
+        let result = execute_assembly_with(&asm, &VmConfig::default(), &natives).unwrap();
+        assert_eq!(result, vec![Value::I32(42)]);
+    }
+
+    #[test]
+    fn calling_an_unregistered_native_is_a_vm_error() {
+        let native = NativeFuncDef { name: "missing".into(), args: 0, returns: false };
+        let main = managed(0, false, vec![], vec![Inst::call(1), Inst::ret]);
+        let asm = Assembly {
+            entry: 0,
+            name: "test".into(),
+            functions: vec![FuncDef::Managed(main), FuncDef::Native(native)],
+            globals: vec![],
+        };
+
+        match execute_assembly(&asm) {
+            Err(VmError::UndefinedNative(ref name)) if name == "missing" => (),
+            other => panic!("expected VmError::UndefinedNative(\"missing\"), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn call_stack_deeper_than_the_configured_limit_is_a_vm_error() {
+        let recurse = managed(0, false, vec![], vec![Inst::call(0), Inst::ret]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(recurse)], globals: vec![] };
+        let config = VmConfig { max_call_depth: 4, max_value_stack: 1024 };
+
+        match execute_assembly_with_config(&asm, &config) {
+            Err(VmError::CallStackOverflow) => (),
+            other => panic!("expected VmError::CallStackOverflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn value_stack_deeper_than_the_configured_limit_is_a_vm_error() {
+        let main = managed(0, false, vec![], vec![Inst::ldc_i32(1), Inst::ldc_i32(2), Inst::ldc_i32(3)]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+        let config = VmConfig { max_call_depth: 16, max_value_stack: 2 };
+
+        match execute_assembly_with_config(&asm, &config) {
+            Err(VmError::ValueStackOverflow) => (),
+            other => panic!("expected VmError::ValueStackOverflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn operand_type_mismatch_is_a_vm_error() {
+        let main = managed(0, false, vec![], vec![Inst::ldc_i32(1), Inst::ldc_i32(2), Inst::add_f32]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+
+        match execute_assembly(&asm) {
+            Err(VmError::TypeMismatch) => (),
+            other => panic!("expected VmError::TypeMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_uncaught_throw_as_vm_error() {
+        let main = managed(0, false, vec![], vec![
+            Inst::ldc_i32(42),
+            Inst::throw,
+        ]);
+        let asm = Assembly { entry: 0, name: "test".into(), functions: vec![FuncDef::Managed(main)], globals: vec![] };
+
+        match execute_assembly(&asm) {
+            Err(VmError::UncaughtException(Value::I32(42))) => (),
+            other => panic!("expected VmError::UncaughtException(I32(42)), got {:?}", other),
+        }
+    }
 }
 
 fn print_managed_debug_info(function: &ManagedFuncDef, frame: &ManagedCallFrame) {
@@ -222,11 +762,11 @@ fn print_managed_debug_info(function: &ManagedFuncDef, frame: &ManagedCallFrame)
 
     println!("Stack:");
     for (idx, value) in frame.stack.iter().enumerate() {
-        println!("  [{:0>4}] 0x{:0>8x}", frame.stack.len() - idx - 1, value);
+        println!("  [{:0>4}] {}", frame.stack.len() - idx - 1, value);
     }
 
     println!("Locals:");
     for (idx, value) in frame.locals.iter().enumerate() {
-        println!("  [{:0>4}] 0x{:0>8x}", idx, value);
+        println!("  [{:0>4}] {}", idx, value);
     }
 }
@@ -0,0 +1,9 @@
+extern crate rand;
+
+pub mod assembly;
+pub mod binary;
+pub mod error;
+pub mod instructions;
+pub mod interpreter;
+pub mod loader;
+pub mod value;
@@ -1,15 +1,18 @@
 use ::std::collections::HashMap;
 use ::std::str::FromStr;
 use super::instructions::Inst;
-use super::assembly::{Assembly, FuncDef};
+use super::assembly::{Assembly, FuncDef, ManagedFuncDef};
+use super::binary;
+use super::value::Value;
 
 pub struct Loader {
     functions: Vec<FuncDef>,
     pending_labels: Vec<String>,
     label_offsets: HashMap<String, usize>,
     labels: Vec<String>,
-    current_func: Option<FuncDef>,
+    current_func: Option<ManagedFuncDef>,
     called_names: Vec<String>,
+    globals: Vec<u32>,
 }
 
 impl Loader {
@@ -21,6 +24,7 @@ impl Loader {
             labels: Vec::new(),
             current_func: None,
             called_names: Vec::new(),
+            globals: Vec::new(),
         }
     }
 
@@ -47,14 +51,24 @@ impl Loader {
         self.save_func();
         self.fill_call_placeholders();
         Assembly {
+            entry: 0,
             name: path.into(),
             functions: self.functions.clone(),
+            globals: self.globals.clone(),
         }
     }
 
+    pub fn load_binary(&self, path: &str) -> Result<Assembly, binary::BinaryError> {
+        use std::fs;
+
+        let bytes = fs::read(path).unwrap();
+        binary::disassemble(&bytes)
+    }
+
     fn fill_call_placeholders(&mut self) {
         let mut changes = vec![];
         for (caller_idx, caller) in self.functions.iter().enumerate() {
+            let caller = caller.as_managed().expect("text assemblies only define managed functions");
             for (inst_idx, inst) in caller.body.iter().enumerate() {
                 if let Inst::call(fake_idx) = inst {
                     let real_idx = self.get_real_func_index(*fake_idx);
@@ -64,7 +78,8 @@ impl Loader {
         }
 
         for (caller_idx, inst_idx, real_callee_idx) in changes {
-            if let Inst::call(ref mut callee_idx) = self.functions[caller_idx].body[inst_idx] {
+            let caller = self.functions[caller_idx].as_managed_mut().unwrap();
+            if let Inst::call(ref mut callee_idx) = caller.body[inst_idx] {
                 *callee_idx = real_callee_idx;
             }
         }
@@ -73,7 +88,7 @@ impl Loader {
     fn get_real_func_index(&self, fake_idx: u16) -> u16 {
         let callee_name = &self.called_names[fake_idx as usize];
         self.functions.iter()
-            .position(|x| &x.name == callee_name)
+            .position(|x| x.name() == callee_name)
             .map(|idx| idx as u16)
             .expect("no such func")
     }
@@ -84,6 +99,7 @@ impl Loader {
             let new_inst = match *inst {
                 Inst::jump(idx) => Inst::jump(self.get_real_instruction_offset(idx)),
                 Inst::beq(idx) => Inst::beq(self.get_real_instruction_offset(idx)),
+                Inst::try_(idx) => Inst::try_(self.get_real_instruction_offset(idx)),
                 _ => continue,
             };
             changes.push((index, new_inst));
@@ -114,7 +130,7 @@ impl Loader {
                 let args = parts.next().unwrap().parse().unwrap();
                 let returns = parts.next().unwrap().parse().unwrap();
 
-                self.current_func = Some(FuncDef {
+                self.current_func = Some(ManagedFuncDef {
                     name,
                     args,
                     returns,
@@ -129,16 +145,24 @@ impl Loader {
             "locals" => {
                 if let Some(ref mut func) = self.current_func {
                     let count = parts.next().unwrap().parse().unwrap();
-                    func.default_locals = vec![0; count];
+                    func.default_locals = vec![Value::I32(0); count];
                 }
             }
             "local" => {
                 let idx: u16 = parts.next().unwrap().parse().unwrap();
-                let value = parts.next().unwrap().parse().unwrap();
+                let value: u32 = parts.next().unwrap().parse().unwrap();
                 if let Some(ref mut func) = self.current_func {
-                    func.default_locals[idx as usize] = value;
+                    func.default_locals[idx as usize] = Value::I32(value);
                 }
             }
+            "global" => {
+                let idx: usize = parts.next().unwrap().parse().unwrap();
+                let value: u32 = parts.next().unwrap().parse().unwrap();
+                if idx >= self.globals.len() {
+                    self.globals.resize(idx + 1, 0);
+                }
+                self.globals[idx] = value;
+            }
             unknown => eprintln!("unknown meta: '{}'", unknown)
         }
     }
@@ -151,9 +175,9 @@ impl Loader {
         if let Some(mut func) = self.current_func.take() {
             let default_locals = func.args + if func.returns { 1 } else { 0 };
             if (default_locals as usize) > func.default_locals.len() {
-                func.default_locals.resize(default_locals as usize, 0);
+                func.default_locals.resize(default_locals as usize, Value::I32(0));
             }
-            self.functions.push(func);
+            self.functions.push(FuncDef::Managed(func));
         }
     }
 
@@ -174,10 +198,51 @@ impl Loader {
                 let label = parts.next().unwrap();
                 Inst::beq(self.get_placeholder_for_label(label) as u32)
             }
+            "try" => {
+                let label = parts.next().unwrap();
+                Inst::try_(self.get_placeholder_for_label(label) as u32)
+            }
+            "endtry" => Inst::endtry,
+            "throw" => Inst::throw,
             "add.u" => Inst::add_u,
             "add.s" => Inst::add_s,
             "sub.u" => Inst::sub_u,
             "sub.s" => Inst::sub_s,
+            "mul.u" => Inst::mul_u,
+            "mul.s" => Inst::mul_s,
+            "div.u" => Inst::div_u,
+            "div.s" => Inst::div_s,
+            "mod.u" => Inst::mod_u,
+            "mod.s" => Inst::mod_s,
+            "shl" => Inst::shl,
+            "shr.u" => Inst::shr_u,
+            "shr.s" => Inst::shr_s,
+            "and" => Inst::and,
+            "or" => Inst::or,
+            "xor" => Inst::xor,
+            "cmp.eq" => Inst::cmp_eq,
+            "cmp.lt.u" => Inst::cmp_lt_u,
+            "cmp.lt.s" => Inst::cmp_lt_s,
+            "cmp.gt.u" => Inst::cmp_gt_u,
+            "cmp.gt.s" => Inst::cmp_gt_s,
+            "ldc.i32" => Inst::ldc_i32(parse_operand(&mut parts)),
+            "ldc.i64" => Inst::ldc_i64(parse_operand(&mut parts)),
+            "ldc.f32" => Inst::ldc_f32(parse_operand(&mut parts)),
+            "ldc.f64" => Inst::ldc_f64(parse_operand(&mut parts)),
+            "add.f32" => Inst::add_f32,
+            "add.f64" => Inst::add_f64,
+            "sub.f32" => Inst::sub_f32,
+            "sub.f64" => Inst::sub_f64,
+            "mul.f32" => Inst::mul_f32,
+            "mul.f64" => Inst::mul_f64,
+            "div.f32" => Inst::div_f32,
+            "div.f64" => Inst::div_f64,
+            "add.i64" => Inst::add_i64,
+            "sub.i64" => Inst::sub_i64,
+            "mul.i64" => Inst::mul_i64,
+            "div.i64" => Inst::div_i64,
+            "ldglobal" => Inst::ldglobal(parse_operand(&mut parts)),
+            "stglobal" => Inst::stglobal(parse_operand(&mut parts)),
             "breakpoint" => Inst::breakpoint,
             "call" => {
                 let func_name = parts.next().unwrap();
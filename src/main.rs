@@ -1,14 +1,14 @@
-mod loader;
-use loader::Loader;
-mod interpreter;
-use interpreter::execute_assembly;
-mod instructions;
-mod assembly;
-use assembly::print_assembly;
+extern crate vm;
+
+use vm::assembly::print_assembly;
+use vm::loader::Loader;
+use vm::interpreter::execute_assembly;
 
 fn main() {
     let mut loader = Loader::new();
     let asm = loader.load("tests/input.asm");
     print_assembly(&asm);
-    execute_assembly(&asm)
+    if let Err(err) = execute_assembly(&asm) {
+        eprintln!("vm error: {}", err);
+    }
 }
@@ -0,0 +1,20 @@
+use ::std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Value {
+    I32(u32),
+    I64(u64),
+    F32(f32),
+    F64(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Value::I32(v) => write!(f, "i32 0x{:0>8x}", v),
+            Value::I64(v) => write!(f, "i64 0x{:0>16x}", v),
+            Value::F32(v) => write!(f, "f32 {}", v),
+            Value::F64(v) => write!(f, "f64 {}", v),
+        }
+    }
+}